@@ -0,0 +1,165 @@
+//! Continuous live capture of HID interrupt traffic.
+//!
+//! Unlike [`crate::write_to_device`], which blocks on one report per
+//! key-naming prompt, this mode polls the selected endpoint(s) in a tight
+//! loop for as long as the process runs, decoding and streaming every
+//! transfer as it arrives.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::hid::decode_boot_report;
+use crate::report_descriptor::ReportDescriptor;
+use crate::Result;
+
+/// One decoded interrupt transfer, serialized as a single NDJSON line.
+#[derive(Debug, Serialize)]
+struct CaptureRecord {
+    ts: u128,
+    endpoint: u8,
+    raw: Vec<u8>,
+    decoded: String,
+}
+
+#[derive(Debug, Default)]
+struct EndpointStats {
+    packets: u64,
+    bytes: u64,
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// Decodes one transfer via the device's HID report descriptor when one is
+/// available, falling back to the fixed boot-keyboard layout for devices
+/// that don't describe their reports (or whose descriptor couldn't be
+/// fetched).
+fn decode_transfer(descriptor: Option<&ReportDescriptor>, buf: &[u8]) -> String {
+    match descriptor {
+        Some(descriptor) if !descriptor.fields.is_empty() => descriptor.describe(buf),
+        _ => match <[u8; 8]>::try_from(buf) {
+            Ok(boot_report) => decode_boot_report(&boot_report).display_string(),
+            Err(_) => format!("{:02x?}", buf),
+        },
+    }
+}
+
+/// Continuously polls `endpoints` on `handler` until Ctrl-C, decoding each
+/// transfer through `descriptor` (falling back to the boot-keyboard layout
+/// when there isn't one) and streaming NDJSON records to `out`. Prints a
+/// packet counter and per-endpoint byte totals on shutdown.
+pub fn run(
+    handler: &libusb::DeviceHandle,
+    endpoints: &[u8],
+    descriptor: Option<&ReportDescriptor>,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || {
+        handler_running.store(false, Ordering::SeqCst);
+    })?;
+
+    let mut stats: HashMap<u8, EndpointStats> = HashMap::new();
+    let mut packet_count: u64 = 0;
+    let start = Instant::now();
+
+    while running.load(Ordering::SeqCst) {
+        for &endpoint in endpoints {
+            let mut buf = [0u8; 64];
+            let size = match handler.read_interrupt(endpoint, &mut buf, Duration::from_millis(50))
+            {
+                Ok(size) => size,
+                Err(_) => continue,
+            };
+
+            if size == 0 {
+                continue;
+            }
+
+            let raw = &buf[..size];
+            let record = CaptureRecord {
+                ts: now_millis(),
+                endpoint,
+                raw: raw.to_vec(),
+                decoded: decode_transfer(descriptor, raw),
+            };
+
+            let line = serde_json::to_string(&record)?;
+            writeln!(out, "{}", line)?;
+
+            packet_count += 1;
+            let entry = stats.entry(endpoint).or_default();
+            entry.packets += 1;
+            entry.bytes += size as u64;
+        }
+    }
+
+    out.flush()?;
+
+    println!(
+        "[capture] stopped after {:.1}s, {} packets",
+        start.elapsed().as_secs_f32(),
+        packet_count
+    );
+    for (endpoint, stat) in stats.iter() {
+        println!(
+            "[capture]   endpoint {:#04x}: {} packets, {} bytes",
+            endpoint, stat.packets, stat.bytes
+        );
+    }
+
+    Ok(())
+}
+
+/// Creates `path` (truncating any existing file) for NDJSON records, or
+/// falls back to stdout when no path is given.
+pub fn open_output(path: &Option<String>) -> Result<Box<dyn Write>> {
+    match path {
+        Some(path) => Ok(Box::new(File::create(path)?)),
+        None => Ok(Box::new(std::io::stdout())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report_descriptor::parse;
+
+    #[test]
+    fn falls_back_to_boot_report_without_a_descriptor() {
+        let buf = [0, 0, 0x04, 0, 0, 0, 0, 0];
+        assert_eq!(decode_transfer(None, &buf), "a");
+    }
+
+    #[test]
+    fn falls_back_to_hex_when_boot_report_wont_fit() {
+        let buf = [0x01, 0x02, 0x03];
+        assert_eq!(decode_transfer(None, &buf), "[01, 02, 03]");
+    }
+
+    #[test]
+    fn uses_report_descriptor_when_available() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x30, // Usage (X)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input
+        ];
+        let descriptor = parse(&bytes);
+
+        assert_eq!(decode_transfer(Some(&descriptor), &[0x05]), "0x1:[48]=5");
+    }
+}
@@ -0,0 +1,151 @@
+//! Decoding of USB HID boot-protocol keyboard reports.
+//!
+//! A boot keyboard report is a fixed 8-byte layout: byte 0 is a modifier
+//! bitmask, byte 1 is reserved, and bytes 2-7 hold up to six simultaneously
+//! pressed usage codes from the Keyboard/Keypad usage page.
+
+const MODIFIERS: [(u8, &str); 8] = [
+    (0b0000_0001, "LeftCtrl"),
+    (0b0000_0010, "LeftShift"),
+    (0b0000_0100, "LeftAlt"),
+    (0b0000_1000, "LeftGUI"),
+    (0b0001_0000, "RightCtrl"),
+    (0b0010_0000, "RightShift"),
+    (0b0100_0000, "RightAlt"),
+    (0b1000_0000, "RightGUI"),
+];
+
+/// Maps a Keyboard/Keypad usage page ID (bytes 2-7 of a boot report) to its
+/// symbolic name. Returns `None` for 0x00 and for every code outside this
+/// table, including 0x02, 0x03, 0x32 and all codes above F12 (0x45).
+fn usage_name(code: u8) -> Option<&'static str> {
+    match code {
+        0x00 => None,
+        0x01 => Some("ErrorRollOver"),
+        0x04..=0x1D => {
+            const LETTERS: &str = "abcdefghijklmnopqrstuvwxyz";
+            let index = (code - 0x04) as usize;
+            LETTERS.get(index..index + 1)
+        }
+        0x1E..=0x26 => {
+            const DIGITS: &str = "123456789";
+            let index = (code - 0x1E) as usize;
+            DIGITS.get(index..index + 1)
+        }
+        0x27 => Some("0"),
+        0x28 => Some("Enter"),
+        0x29 => Some("Escape"),
+        0x2A => Some("Backspace"),
+        0x2B => Some("Tab"),
+        0x2C => Some("Space"),
+        0x2D => Some("Minus"),
+        0x2E => Some("Equal"),
+        0x2F => Some("LeftBracket"),
+        0x30 => Some("RightBracket"),
+        0x31 => Some("Backslash"),
+        0x33 => Some("Semicolon"),
+        0x34 => Some("Apostrophe"),
+        0x35 => Some("Grave"),
+        0x36 => Some("Comma"),
+        0x37 => Some("Period"),
+        0x38 => Some("Slash"),
+        0x39 => Some("CapsLock"),
+        0x3A..=0x45 => {
+            const FUNCTION_KEYS: [&str; 12] = [
+                "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+            ];
+            FUNCTION_KEYS.get((code - 0x3A) as usize).copied()
+        }
+        _ => None,
+    }
+}
+
+/// A boot-protocol keyboard report decoded into symbolic names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedReport {
+    pub modifiers: Vec<String>,
+    pub keys: Vec<String>,
+    pub rollover_error: bool,
+}
+
+impl DecodedReport {
+    /// Renders the decoded report the way it should be printed to the user,
+    /// e.g. `LeftCtrl+LeftShift+k`.
+    pub fn display_string(&self) -> String {
+        if self.rollover_error {
+            return "[rollover error]".to_string();
+        }
+
+        self.modifiers
+            .iter()
+            .chain(self.keys.iter())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+}
+
+/// Decodes a raw 8-byte boot-protocol keyboard report into symbolic key
+/// names. A `0x01` usage code in any key slot means a rollover error: more
+/// keys are pressed than the device can report at once.
+pub fn decode_boot_report(report: &[u8; 8]) -> DecodedReport {
+    let modifiers = MODIFIERS
+        .iter()
+        .filter(|(bit, _)| report[0] & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+
+    let rollover_error = report[2..8].contains(&0x01);
+
+    let keys = if rollover_error {
+        Vec::new()
+    } else {
+        report[2..8]
+            .iter()
+            .filter_map(|&code| usage_name(code))
+            .map(|name| name.to_string())
+            .collect()
+    };
+
+    DecodedReport {
+        modifiers,
+        keys,
+        rollover_error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_plain_letter() {
+        let decoded = decode_boot_report(&[0, 0, 0x04, 0, 0, 0, 0, 0]);
+        assert_eq!(decoded.modifiers, Vec::<String>::new());
+        assert_eq!(decoded.keys, vec!["a".to_string()]);
+        assert!(!decoded.rollover_error);
+        assert_eq!(decoded.display_string(), "a");
+    }
+
+    #[test]
+    fn decodes_modifiers_and_multiple_keys() {
+        let decoded = decode_boot_report(&[0b0000_0011, 0, 0x2C, 0x3A, 0, 0, 0, 0]);
+        assert_eq!(decoded.modifiers, vec!["LeftCtrl", "LeftShift"]);
+        assert_eq!(decoded.keys, vec!["Space", "F1"]);
+        assert_eq!(decoded.display_string(), "LeftCtrl+LeftShift+Space+F1");
+    }
+
+    #[test]
+    fn flags_rollover_error() {
+        let decoded = decode_boot_report(&[0, 0, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01]);
+        assert!(decoded.rollover_error);
+        assert!(decoded.keys.is_empty());
+        assert_eq!(decoded.display_string(), "[rollover error]");
+    }
+
+    #[test]
+    fn ignores_empty_key_slots() {
+        let decoded = decode_boot_report(&[0, 0, 0x04, 0, 0, 0, 0, 0]);
+        assert_eq!(decoded.keys.len(), 1);
+    }
+}
@@ -9,11 +9,24 @@ use libusb::{
 };
 use serde::{Deserialize, Serialize};
 
+mod hid;
+use hid::decode_boot_report;
+
+mod report_descriptor;
+
+mod usbip;
+
+mod capture;
+
+const GET_DESCRIPTOR: u8 = 0x06;
+
 #[derive(Debug, Clone)]
 pub enum Mode {
     Info,
     Read,
     Write,
+    Server,
+    Capture,
 }
 
 impl From<&str> for Mode {
@@ -22,6 +35,8 @@ impl From<&str> for Mode {
             "info" => Self::Info,
             "read" => Self::Read,
             "write" => Self::Write,
+            "server" => Self::Server,
+            "capture" => Self::Capture,
             _ => {
                 println!("[Error] {} is not a valid mode", s);
                 process::exit(1);
@@ -49,9 +64,33 @@ pub struct AppArgs {
     #[arg(short)]
     device: Option<String>,
 
-    /// Operation mode: [info, read].
+    /// Operation mode: [info, read, write, server, capture].
     #[arg(short)]
     mode: Mode,
+
+    /// Key name to replay from config.json, prompts for one when omitted.
+    #[arg(short)]
+    key: Option<String>,
+
+    /// Number of times to replay the selected key (write mode).
+    #[arg(short, default_value_t = 1)]
+    repeat: u32,
+
+    /// Delay in milliseconds between replayed reports (write mode).
+    #[arg(short = 't', default_value_t = 0)]
+    delay_ms: u64,
+
+    /// Address to listen on for USB/IP clients (server mode).
+    #[arg(short = 'a', default_value = "0.0.0.0:3240")]
+    address: String,
+
+    /// USB/IP bus ID to export the device under, e.g. "1-1" (server mode).
+    #[arg(short = 'b', default_value = "1-1")]
+    busid: String,
+
+    /// File to stream captured NDJSON records to, defaults to stdout (capture mode).
+    #[arg(short)]
+    output: Option<String>,
 }
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -153,6 +192,36 @@ impl ClassCode {
             _ => Self::Unknown,
         }
     }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Self::Audio => 0x01,
+            Self::CommunicationAndCdcDescriptors => 0x02,
+            Self::HumanInterfaceDevice => 0x03,
+            Self::Physical => 0x05,
+            Self::Image => 0x06,
+            Self::Printer => 0x07,
+            Self::MassStorage => 0x08,
+            Self::Hub => 0x09,
+            Self::CdcData => 0x0A,
+            Self::SmartCard => 0x0B,
+            Self::ContentSecurity => 0x0D,
+            Self::Video => 0x0E,
+            Self::PersonalHealthcare => 0x0F,
+            Self::AudioVideoDevices => 0x10,
+            Self::BillboardDeviceClass => 0x11,
+            Self::USBTypeCBridgeClass => 0x12,
+            Self::USBBulkDisplayProtocolDeviceClass => 0x13,
+            Self::MCTPOverUSBProtocolDeviceClass => 0x14,
+            Self::I3CDeviceClass => 0x3C,
+            Self::DiagnosticDevice => 0xDC,
+            Self::WirelessController => 0xE0,
+            Self::Miscellaneous => 0xEF,
+            Self::ApplicationSpecific => 0xFE,
+            Self::VenderSpecific => 0xFF,
+            Self::Unknown => 0x00,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -165,9 +234,12 @@ pub struct DeviceInfo {
     // pub usb_version: Version,
     pub protocol_code: u8,
     pub max_packet_size: u8,
-    // pub product_string_index: Option<u8>,
-    // pub manufacturer_string_index: Option<u8>,
-    // pub serial_number_string_index: Option<u8>,
+    pub product_string_index: Option<u8>,
+    pub manufacturer_string_index: Option<u8>,
+    pub serial_number_string_index: Option<u8>,
+    pub product: Option<String>,
+    pub manufacturer: Option<String>,
+    pub serial_number: Option<String>,
     // pub type_id: TypeId,
     pub configurations: Vec<ConfigDescriptor>,
 }
@@ -243,6 +315,60 @@ impl Endpoint {
     }
 }
 
+const DESCRIPTOR_TYPE_STRING: u16 = 0x03;
+const DEFAULT_LANGID: u16 = 0x0409;
+
+/// Reads the string-descriptor language table (index 0) and returns the
+/// first available LANGID, falling back to English (US) when a device
+/// doesn't expose one or denies the control transfer.
+fn read_default_langid(handler: &libusb::DeviceHandle) -> u16 {
+    let mut buf = [0u8; 255];
+    match handler.read_control(
+        0x80,
+        GET_DESCRIPTOR,
+        DESCRIPTOR_TYPE_STRING << 8,
+        0,
+        &mut buf,
+        Duration::from_millis(200),
+    ) {
+        Ok(read) if read >= 4 => u16::from_le_bytes([buf[2], buf[3]]),
+        _ => DEFAULT_LANGID,
+    }
+}
+
+/// Fetches and UTF-16LE-decodes the string descriptor at `index`, or `None`
+/// if the index is unset (0) or the device denies the request.
+fn read_string_descriptor(
+    handler: &libusb::DeviceHandle,
+    index: Option<u8>,
+    langid: u16,
+) -> Option<String> {
+    let index = index.filter(|i| *i != 0)?;
+
+    let mut buf = [0u8; 255];
+    let read = handler
+        .read_control(
+            0x80,
+            GET_DESCRIPTOR,
+            DESCRIPTOR_TYPE_STRING << 8 | index as u16,
+            langid,
+            &mut buf,
+            Duration::from_millis(200),
+        )
+        .ok()?;
+
+    if read < 2 {
+        return None;
+    }
+
+    let units: Vec<u16> = buf[2..read]
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+
+    Some(String::from_utf16_lossy(&units))
+}
+
 #[derive(Debug, Clone)]
 pub struct UsbDevices {
     devices: Vec<DeviceInfo>,
@@ -261,6 +387,18 @@ impl UsbDevices {
         for i in devices.iter() {
             let d = i.device_descriptor()?;
 
+            let (product, manufacturer, serial_number) = match i.open() {
+                Ok(handler) => {
+                    let langid = read_default_langid(&handler);
+                    (
+                        read_string_descriptor(&handler, d.product_string_index(), langid),
+                        read_string_descriptor(&handler, d.manufacturer_string_index(), langid),
+                        read_string_descriptor(&handler, d.serial_number_string_index(), langid),
+                    )
+                }
+                Err(_) => (None, None, None),
+            };
+
             let mut config = DeviceInfo {
                 vendor_id: format!("{:04x}", d.vendor_id()),
                 product_id: format!("{:04x}", d.product_id()),
@@ -269,6 +407,12 @@ impl UsbDevices {
                 num_configurations: d.num_configurations(),
                 protocol_code: d.protocol_code(),
                 max_packet_size: d.max_packet_size(),
+                product_string_index: d.product_string_index(),
+                manufacturer_string_index: d.manufacturer_string_index(),
+                serial_number_string_index: d.serial_number_string_index(),
+                product,
+                manufacturer,
+                serial_number,
                 configurations: Vec::new(),
             };
 
@@ -329,6 +473,8 @@ pub fn get_device_info(dev: &Option<String>) -> Result<()> {
 struct Keymap {
     key_name: String,
     map: [u8; 8],
+    /// Symbolic key names decoded from `map`, e.g. `["LeftShift", "k"]`.
+    decoded: Vec<String>,
 }
 
 fn store_keymap() {}
@@ -386,12 +532,14 @@ pub fn write_to_device(args: &AppArgs) {
         handler.read_interrupt(endpoint, &mut buf, Duration::default());
 
         let keyname = s.strip_suffix("\n").unwrap();
-        println!("Key {} => {:?}", keyname, buf);
+        let decoded = decode_boot_report(&buf);
+        println!("Key {} => {:?} ({})", keyname, buf, decoded.display_string());
         println!("---------------------------------------------------------");
 
         keymaps.push(Keymap {
             key_name: keyname.to_string(),
             map: buf,
+            decoded: decoded.modifiers.into_iter().chain(decoded.keys).collect(),
         });
 
         'options: loop {
@@ -418,6 +566,185 @@ pub fn write_to_device(args: &AppArgs) {
     file.write_all(map.as_bytes());
 }
 
+const HID_SET_REPORT: u8 = 0x09;
+const HID_REPORT_TYPE_OUTPUT: u16 = 0x02;
+
+/// Find an interrupt OUT endpoint on the given interface, if the device exposes one.
+fn find_interrupt_out_endpoint(device: &str, configuration: u8, interface: u8) -> Option<u8> {
+    let info = UsbDevices::new().ok()?.get_by_id(device)?;
+
+    info.configurations
+        .iter()
+        .find(|c| c.number == configuration)?
+        .interfaces
+        .iter()
+        .find(|i| i.interface_number == interface)?
+        .endpoints
+        .iter()
+        .find(|e| e.direction == Direction::Out && e.transfer_type == TransferType::Interrupt)
+        .map(|e| e.address)
+}
+
+fn send_report(
+    handler: &libusb::DeviceHandle,
+    out_endpoint: Option<u8>,
+    interface: u8,
+    report: &[u8; 8],
+) -> Result<()> {
+    if let Some(out_endpoint) = out_endpoint {
+        handler.write_interrupt(out_endpoint, report, Duration::from_millis(100))?;
+    } else {
+        handler.write_control(
+            0x21,
+            HID_SET_REPORT,
+            HID_REPORT_TYPE_OUTPUT << 8,
+            interface as u16,
+            report,
+            Duration::from_millis(100),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn replay_to_device(args: &AppArgs) -> Result<()> {
+    let interface = args.interface.unwrap_or(1);
+    let config = args.configuration.unwrap();
+    let device = args.device.as_ref().unwrap();
+
+    let ctx = libusb::Context::new()?;
+
+    let dev = ctx
+        .devices()?
+        .iter()
+        .find(|d| {
+            let descriptor = d.device_descriptor().unwrap();
+            let name = format!(
+                "{:04x}:{:04x}",
+                descriptor.vendor_id(),
+                descriptor.product_id()
+            );
+            name == *device
+        })
+        .ok_or("device not found")?;
+
+    let mut handler = dev.open()?;
+    handler.set_active_configuration(config)?;
+    handler.detach_kernel_driver(interface)?;
+    handler.claim_interface(interface)?;
+
+    let out_endpoint = find_interrupt_out_endpoint(device, config, interface);
+
+    let file = File::open("config.json")?;
+    let keymaps: Vec<Keymap> = serde_json::from_reader(file)?;
+
+    let keyname = if let Some(key) = &args.key {
+        key.clone()
+    } else {
+        println!("Available keys:");
+        for keymap in &keymaps {
+            println!("  {}", keymap.key_name);
+        }
+        print!("Select a key to replay: ");
+        stdout().flush()?;
+
+        let mut s = String::new();
+        stdin().read_line(&mut s)?;
+        s.trim().to_string()
+    };
+
+    let keymap = keymaps
+        .iter()
+        .find(|k| k.key_name == keyname)
+        .ok_or(format!("key {} not found in config.json", keyname))?;
+
+    for n in 0..args.repeat {
+        send_report(&handler, out_endpoint, interface, &keymap.map)?;
+        println!("[{}/{}] replayed {} => {:?}", n + 1, args.repeat, keymap.key_name, keymap.map);
+
+        if args.delay_ms > 0 {
+            std::thread::sleep(Duration::from_millis(args.delay_ms));
+        }
+    }
+
+    Ok(())
+}
+
+/// Exports the selected device over USB/IP so a remote host can attach it,
+/// replaying captured `config.json` reports on its interrupt IN endpoint.
+pub fn serve_device(args: &AppArgs) -> Result<()> {
+    let device = args.device.as_ref().unwrap();
+    let info = UsbDevices::new()?
+        .get_by_id(device)
+        .ok_or("device not found")?;
+
+    usbip::serve(&args.address, &args.busid, info)
+}
+
+/// All IN endpoint addresses on the given interface/configuration.
+fn interrupt_in_endpoints(device: &str, configuration: u8, interface: u8) -> Result<Vec<u8>> {
+    let info = UsbDevices::new()?
+        .get_by_id(device)
+        .ok_or("device not found")?;
+
+    let endpoints = info
+        .configurations
+        .iter()
+        .find(|c| c.number == configuration)
+        .ok_or("configuration not found")?
+        .interfaces
+        .iter()
+        .find(|i| i.interface_number == interface)
+        .ok_or("interface not found")?
+        .endpoints
+        .iter()
+        .filter(|e| e.direction == Direction::In)
+        .map(|e| e.address)
+        .collect();
+
+    Ok(endpoints)
+}
+
+/// Continuously captures interrupt traffic from the selected endpoint, or
+/// every IN endpoint of the selected interface when none is given.
+pub fn capture_device(args: &AppArgs) -> Result<()> {
+    let interface = args.interface.unwrap_or(1);
+    let config = args.configuration.unwrap();
+    let device = args.device.as_ref().unwrap();
+
+    let endpoints = match args.endpoint {
+        Some(endpoint) => vec![endpoint],
+        None => interrupt_in_endpoints(device, config, interface)?,
+    };
+
+    let ctx = libusb::Context::new()?;
+    let dev = ctx
+        .devices()?
+        .iter()
+        .find(|d| {
+            let descriptor = d.device_descriptor().unwrap();
+            let name = format!(
+                "{:04x}:{:04x}",
+                descriptor.vendor_id(),
+                descriptor.product_id()
+            );
+            name == *device
+        })
+        .ok_or("device not found")?;
+
+    let mut handler = dev.open()?;
+    handler.set_active_configuration(config)?;
+    handler.detach_kernel_driver(interface)?;
+    handler.claim_interface(interface)?;
+
+    // Non-boot devices (mice, gamepads, vendor HID) need their report
+    // descriptor to decode anything beyond raw bytes; boot keyboards work
+    // either way, so a failed fetch just falls back to the boot layout.
+    let descriptor = report_descriptor::fetch(&handler, interface, 4096).ok();
+
+    let mut out = capture::open_output(&args.output)?;
+    capture::run(&handler, &endpoints, descriptor.as_ref(), &mut *out)
+}
+
 fn main() -> Result<()> {
     let args = AppArgs::parse();
 
@@ -429,7 +756,15 @@ fn main() -> Result<()> {
         Mode::Read => {
             write_to_device(&args);
         }
-        Mode::Write => todo!(),
+        Mode::Write => {
+            replay_to_device(&args)?;
+        }
+        Mode::Server => {
+            serve_device(&args)?;
+        }
+        Mode::Capture => {
+            capture_device(&args)?;
+        }
     };
 
     Ok(())
@@ -0,0 +1,351 @@
+//! Parsing of HID class-specific report descriptors.
+//!
+//! The boot-keyboard layout assumed elsewhere in this crate only covers a
+//! fixed 8-byte report. Real HID devices (mice, gamepads, vendor devices)
+//! describe their report layout in a separate descriptor fetched from the
+//! device itself; this module walks that descriptor's item stream and turns
+//! it into a flat list of named bit-fields.
+
+use std::time::Duration;
+
+use crate::Result;
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const HID_REPORT_DESCRIPTOR_TYPE: u16 = 0x22;
+
+/// Which HID Main item produced a [`Field`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MainItemKind {
+    Input,
+    Output,
+    Feature,
+}
+
+/// A single named bit-field within a HID report, spanning
+/// `report_size * report_count` bits and tagged with the usages that were
+/// active when the enclosing Main item was emitted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub kind: MainItemKind,
+    pub report_id: Option<u8>,
+    pub usage_page: u32,
+    pub usages: Vec<u32>,
+    pub report_size: u32,
+    pub report_count: u32,
+    pub logical_min: i32,
+    pub logical_max: i32,
+    pub bit_offset: usize,
+}
+
+impl Field {
+    /// Total width of this field across all its `report_count` repeats.
+    pub fn bit_len(&self) -> usize {
+        (self.report_size * self.report_count) as usize
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct GlobalState {
+    usage_page: u32,
+    report_size: u32,
+    report_count: u32,
+    logical_min: i32,
+    logical_max: i32,
+    report_id: Option<u8>,
+}
+
+/// A parsed HID report descriptor: an ordered list of bit-fields, one per
+/// Main item, each tagged with the report ID it belongs to (if the device
+/// declares more than one report).
+#[derive(Debug, Clone, Default)]
+pub struct ReportDescriptor {
+    pub fields: Vec<Field>,
+}
+
+impl ReportDescriptor {
+    /// Fields belonging to a specific report ID, or all fields if the
+    /// device doesn't use Report IDs.
+    pub fn fields_for_report(&self, report_id: Option<u8>) -> Vec<&Field> {
+        self.fields
+            .iter()
+            .filter(|f| f.report_id == report_id)
+            .collect()
+    }
+
+    /// Whether any field in this descriptor is tagged with a Report ID,
+    /// meaning the first byte of every transfer selects which report it is.
+    fn uses_report_ids(&self) -> bool {
+        self.fields.iter().any(|f| f.report_id.is_some())
+    }
+
+    /// Slices a raw interrupt transfer into its Input fields and renders
+    /// each as `usage_page:usages=value`, joined by `, `. Returns an empty
+    /// string when this descriptor has no fields covering `data`.
+    pub fn describe(&self, data: &[u8]) -> String {
+        let (report_id, data) = if self.uses_report_ids() {
+            match data.split_first() {
+                Some((id, rest)) => (Some(*id), rest),
+                None => return String::new(),
+            }
+        } else {
+            (None, data)
+        };
+
+        self.fields_for_report(report_id)
+            .iter()
+            .filter(|f| f.kind == MainItemKind::Input)
+            .map(|f| {
+                let value = extract_bits(data, f.bit_offset, f.bit_len());
+                format!("{:#x}:{:?}={}", f.usage_page, f.usages, value)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Reads `bit_len` bits (LSB-first, the HID report packing order) starting
+/// at `bit_offset` out of `data` and returns them as an unsigned integer.
+/// Fields wider than 32 bits are truncated.
+fn extract_bits(data: &[u8], bit_offset: usize, bit_len: usize) -> u32 {
+    let mut value: u32 = 0;
+    for i in 0..bit_len.min(32) {
+        let bit_index = bit_offset + i;
+        let byte_index = bit_index / 8;
+        if byte_index >= data.len() {
+            break;
+        }
+        let bit = (data[byte_index] >> (bit_index % 8)) & 1;
+        value |= (bit as u32) << i;
+    }
+    value
+}
+
+/// Parses a raw HID report descriptor byte stream into a [`ReportDescriptor`].
+pub fn parse(bytes: &[u8]) -> ReportDescriptor {
+    let mut fields = Vec::new();
+    let mut global = GlobalState::default();
+    let mut global_stack: Vec<GlobalState> = Vec::new();
+    let mut local_usages: Vec<u32> = Vec::new();
+    let mut usage_minimum: Option<u32> = None;
+    // Input, Output and Feature reports are laid out independently, so each
+    // kind keeps its own running bit offset (indexed by `MainItemKind`).
+    let mut bit_offsets = [0usize; 3];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let prefix = bytes[i];
+        let size = match prefix & 0x03 {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            3 => 4,
+            _ => unreachable!(),
+        };
+        let item_type = (prefix >> 2) & 0x03;
+        let tag = (prefix >> 4) & 0x0F;
+
+        i += 1;
+        if i + size > bytes.len() {
+            break;
+        }
+        let data = &bytes[i..i + size];
+        i += size;
+
+        let value_u32 = {
+            let mut v = 0u32;
+            for (shift, byte) in data.iter().enumerate() {
+                v |= (*byte as u32) << (shift * 8);
+            }
+            v
+        };
+        let value_i32 = match size {
+            1 => data[0] as i8 as i32,
+            2 => i16::from_le_bytes([data[0], data[1]]) as i32,
+            4 => i32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            _ => 0,
+        };
+
+        match item_type {
+            // Main
+            0 => {
+                match tag << 4 {
+                    0x80 | 0x90 | 0xB0 => {
+                        let kind = match tag << 4 {
+                            0x80 => MainItemKind::Input,
+                            0x90 => MainItemKind::Output,
+                            _ => MainItemKind::Feature,
+                        };
+
+                        let bit_len = (global.report_size * global.report_count) as usize;
+                        let bit_offset = &mut bit_offsets[kind as usize];
+                        fields.push(Field {
+                            kind,
+                            report_id: global.report_id,
+                            usage_page: global.usage_page,
+                            usages: local_usages.clone(),
+                            report_size: global.report_size,
+                            report_count: global.report_count,
+                            logical_min: global.logical_min,
+                            logical_max: global.logical_max,
+                            bit_offset: *bit_offset,
+                        });
+                        *bit_offset += bit_len;
+                        local_usages.clear();
+                        usage_minimum = None;
+                    }
+                    _ => {
+                        // Collection / End Collection: no field-level state to track.
+                        local_usages.clear();
+                        usage_minimum = None;
+                    }
+                }
+            }
+            // Global
+            1 => match tag {
+                0x0 => global.usage_page = value_u32,
+                0x1 => global.logical_min = value_i32,
+                0x2 => global.logical_max = value_i32,
+                0x7 => global.report_size = value_u32,
+                0x8 => {
+                    global.report_id = Some(value_u32 as u8);
+                    bit_offsets = [0; 3];
+                }
+                0x9 => global.report_count = value_u32,
+                0xA => global_stack.push(global.clone()),
+                0xB => {
+                    if let Some(popped) = global_stack.pop() {
+                        global = popped;
+                    }
+                }
+                _ => {}
+            },
+            // Local
+            2 => match tag {
+                0x0 => local_usages.push(value_u32),
+                0x1 => usage_minimum = Some(value_u32),
+                0x2 => {
+                    if let Some(min) = usage_minimum.take() {
+                        local_usages.extend(min..=value_u32);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    ReportDescriptor { fields }
+}
+
+/// Fetches the class-specific HID report descriptor for `interface` from an
+/// already-claimed device handle.
+pub fn fetch(
+    handler: &libusb::DeviceHandle,
+    interface: u8,
+    length: u16,
+) -> Result<ReportDescriptor> {
+    let mut buf = vec![0u8; length as usize];
+    let read = handler.read_control(
+        0x81,
+        GET_DESCRIPTOR,
+        HID_REPORT_DESCRIPTOR_TYPE << 8,
+        interface as u16,
+        &mut buf,
+        Duration::from_millis(200),
+    )?;
+    buf.truncate(read);
+
+    Ok(parse(&buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_usage_minimum_maximum_into_usages() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x19, 0x01, // Usage Minimum (1)
+            0x29, 0x03, // Usage Maximum (3)
+            0x15, 0x00, // Logical Minimum (0)
+            0x25, 0x01, // Logical Maximum (1)
+            0x75, 0x01, // Report Size (1)
+            0x95, 0x03, // Report Count (3)
+            0x81, 0x02, // Input (Data, Var, Abs)
+        ];
+
+        let descriptor = parse(&bytes);
+        assert_eq!(descriptor.fields.len(), 1);
+
+        let field = &descriptor.fields[0];
+        assert_eq!(field.kind, MainItemKind::Input);
+        assert_eq!(field.usage_page, 1);
+        assert_eq!(field.usages, vec![1, 2, 3]);
+        assert_eq!(field.report_size, 1);
+        assert_eq!(field.report_count, 3);
+        assert_eq!(field.bit_offset, 0);
+    }
+
+    #[test]
+    fn report_id_switch_resets_bit_offset() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x85, 0x01, // Report ID (1)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input
+            0x85, 0x02, // Report ID (2)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input
+        ];
+
+        let descriptor = parse(&bytes);
+        assert_eq!(descriptor.fields.len(), 2);
+        assert_eq!(descriptor.fields[0].report_id, Some(1));
+        assert_eq!(descriptor.fields[0].bit_offset, 0);
+        assert_eq!(descriptor.fields[1].report_id, Some(2));
+        assert_eq!(descriptor.fields[1].bit_offset, 0);
+    }
+
+    #[test]
+    fn push_pop_restores_global_state() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x15, 0x00, // Logical Minimum (0)
+            0x25, 0x0A, // Logical Maximum (10)
+            0xA4,       // Push
+            0x25, 0x7F, // Logical Maximum (127), inside the pushed scope
+            0xB4,       // Pop
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input
+        ];
+
+        let descriptor = parse(&bytes);
+        assert_eq!(descriptor.fields.len(), 1);
+        assert_eq!(descriptor.fields[0].logical_min, 0);
+        assert_eq!(descriptor.fields[0].logical_max, 10);
+    }
+
+    #[test]
+    fn describe_slices_fields_out_of_a_transfer() {
+        #[rustfmt::skip]
+        let bytes = [
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x30, // Usage (X)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input
+            0x09, 0x31, // Usage (Y)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input
+        ];
+
+        let descriptor = parse(&bytes);
+        assert_eq!(descriptor.describe(&[0x05, 0x09]), "0x1:[48]=5, 0x1:[49]=9");
+    }
+}
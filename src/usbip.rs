@@ -0,0 +1,542 @@
+//! A minimal USB/IP server that exports a sniffed or synthesized device so
+//! a remote host can `usbip attach` it over TCP.
+//!
+//! This implements just enough of the protocol (op_common handshake,
+//! OP_REQ_DEVLIST/OP_REQ_IMPORT, and USBIP_CMD_SUBMIT) to attach a single
+//! device and drive its transfers through a [`UsbInterfaceHandler`].
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use libusb::{SyncType, TransferType, UsageType};
+
+use crate::{ConfigDescriptor, DeviceInfo, Endpoint, Keymap, Result};
+
+const USBIP_VERSION: u16 = 0x0111;
+
+const OP_REQ_DEVLIST: u16 = 0x8005;
+const OP_REP_DEVLIST: u16 = 0x0005;
+const OP_REQ_IMPORT: u16 = 0x8003;
+const OP_REP_IMPORT: u16 = 0x0003;
+
+const USBIP_CMD_SUBMIT: u32 = 0x0001;
+const USBIP_RET_SUBMIT: u32 = 0x0003;
+const USBIP_CMD_UNLINK: u32 = 0x0002;
+const USBIP_RET_UNLINK: u32 = 0x0004;
+
+/// Driven by incoming URBs for one exported device. Implementors answer
+/// control, interrupt, and bulk transfers however fits the device they're
+/// emulating.
+pub trait UsbInterfaceHandler {
+    /// Handles a single URB and returns the bytes to send back to the
+    /// importing host (the data stage of an IN transfer, or an empty vec
+    /// for an OUT transfer / a transfer with nothing to return).
+    fn handle_urb(
+        &mut self,
+        interface: u8,
+        endpoint: u8,
+        setup_packet: Option<[u8; 8]>,
+        request_bytes: &[u8],
+    ) -> Vec<u8>;
+}
+
+/// Replays a captured [`Keymap`] sequence to an interrupt IN endpoint,
+/// answers standard GET_DESCRIPTOR control requests from the device's
+/// already-enumerated topology, and logs OUT transfers.
+pub struct KeymapReplayHandler {
+    pub device: DeviceInfo,
+    pub keymaps: Vec<Keymap>,
+    next_report: usize,
+}
+
+impl KeymapReplayHandler {
+    pub fn new(device: DeviceInfo, keymaps: Vec<Keymap>) -> Self {
+        Self {
+            device,
+            keymaps,
+            next_report: 0,
+        }
+    }
+
+    fn device_descriptor_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![
+            18,   // bLength
+            0x01, // bDescriptorType: DEVICE
+            0x00, 0x02, // bcdUSB 2.00
+            0x00, // bDeviceClass
+            0x00, // bDeviceSubClass
+            0x00, // bDeviceProtocol
+            self.device.max_packet_size,
+        ];
+        let vendor_id = u16::from_str_radix(&self.device.vendor_id, 16).unwrap_or(0);
+        let product_id = u16::from_str_radix(&self.device.product_id, 16).unwrap_or(0);
+        bytes.extend_from_slice(&vendor_id.to_le_bytes());
+        bytes.extend_from_slice(&product_id.to_le_bytes());
+        bytes.extend_from_slice(&[0x00, 0x00]); // bcdDevice
+        bytes.extend_from_slice(&[0, 0, 0]); // string indices, unused here
+        bytes.push(self.device.num_configurations);
+        bytes
+    }
+
+    fn config_descriptor_bytes(&self, config: &ConfigDescriptor) -> Vec<u8> {
+        let mut attributes = 0x80u8;
+        if config.self_powered {
+            attributes |= 0x40;
+        }
+        if config.remote_wakeup {
+            attributes |= 0x20;
+        }
+
+        let mut sub_descriptors = Vec::new();
+        for interface in &config.interfaces {
+            sub_descriptors.extend_from_slice(&[
+                9,    // bLength
+                0x04, // bDescriptorType: INTERFACE
+                interface.interface_number,
+                0, // bAlternateSetting
+                interface.num_endpoints,
+                interface.class_code.to_u8(),
+                interface.subclass_code,
+                0, // bInterfaceProtocol
+                0, // iInterface
+            ]);
+
+            for endpoint in &interface.endpoints {
+                sub_descriptors.extend_from_slice(&[
+                    7,    // bLength
+                    0x05, // bDescriptorType: ENDPOINT
+                    endpoint.address,
+                    endpoint_attributes(endpoint),
+                ]);
+                sub_descriptors.extend_from_slice(&endpoint.max_packet_size.to_le_bytes());
+                sub_descriptors.push(endpoint.interval);
+            }
+        }
+
+        let total_length = (9 + sub_descriptors.len()) as u16;
+
+        let mut bytes = vec![
+            9,    // bLength
+            0x02, // bDescriptorType: CONFIGURATION
+        ];
+        bytes.extend_from_slice(&total_length.to_le_bytes());
+        bytes.push(config.num_interfaces);
+        bytes.push(config.number);
+        bytes.push(0); // iConfiguration
+        bytes.push(attributes);
+        bytes.push((config.max_power / 2) as u8);
+        bytes.extend_from_slice(&sub_descriptors);
+        bytes
+    }
+}
+
+/// Packs an endpoint's transfer/sync/usage type into a standard
+/// `bmAttributes` byte (bits 0-1 transfer type, 2-3 sync type, 4-5 usage
+/// type; the latter two only matter for isochronous endpoints).
+fn endpoint_attributes(endpoint: &Endpoint) -> u8 {
+    let transfer_bits = match endpoint.transfer_type {
+        TransferType::Control => 0,
+        TransferType::Isochronous => 1,
+        TransferType::Bulk => 2,
+        TransferType::Interrupt => 3,
+    };
+
+    let sync_bits = match endpoint.sync_type {
+        SyncType::NoSynchronization => 0,
+        SyncType::Asynchronous => 1,
+        SyncType::Adaptive => 2,
+        SyncType::Synchronous => 3,
+    };
+
+    let usage_bits = match endpoint.usage_type {
+        UsageType::Data => 0,
+        UsageType::Feedback => 1,
+        _ => 2, // implicit feedback / reserved
+    };
+
+    transfer_bits | (sync_bits << 2) | (usage_bits << 4)
+}
+
+impl UsbInterfaceHandler for KeymapReplayHandler {
+    fn handle_urb(
+        &mut self,
+        _interface: u8,
+        endpoint: u8,
+        setup_packet: Option<[u8; 8]>,
+        request_bytes: &[u8],
+    ) -> Vec<u8> {
+        if let Some(setup) = setup_packet {
+            let request_type = setup[0];
+            let request = setup[1];
+            let descriptor_type = setup[3];
+
+            // Standard GET_DESCRIPTOR on the control endpoint.
+            if request_type & 0x80 != 0 && request == 0x06 {
+                return match descriptor_type {
+                    0x01 => self.device_descriptor_bytes(),
+                    0x02 => self
+                        .device
+                        .configurations
+                        .first()
+                        .map(|c| self.config_descriptor_bytes(c))
+                        .unwrap_or_default(),
+                    _ => Vec::new(),
+                };
+            }
+
+            println!("[usbip] control OUT: {:02x?}", request_bytes);
+            return Vec::new();
+        }
+
+        // Interrupt/bulk IN: hand back the next queued report, wrapping around.
+        if endpoint & 0x80 != 0 {
+            if self.keymaps.is_empty() {
+                return Vec::new();
+            }
+            let keymap = &self.keymaps[self.next_report % self.keymaps.len()];
+            self.next_report += 1;
+            return keymap.map.to_vec();
+        }
+
+        println!("[usbip] endpoint {:#x} OUT: {:02x?}", endpoint, request_bytes);
+        Vec::new()
+    }
+}
+
+fn read_exact_buf(stream: &mut TcpStream, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_op_common(stream: &mut impl Write, code: u16, status: u32) -> Result<()> {
+    stream.write_all(&USBIP_VERSION.to_be_bytes())?;
+    stream.write_all(&code.to_be_bytes())?;
+    stream.write_all(&status.to_be_bytes())?;
+    Ok(())
+}
+
+fn write_fixed_str(stream: &mut impl Write, s: &str, len: usize) -> Result<()> {
+    let mut buf = vec![0u8; len];
+    let bytes = s.as_bytes();
+    let n = bytes.len().min(len);
+    buf[..n].copy_from_slice(&bytes[..n]);
+    stream.write_all(&buf)?;
+    Ok(())
+}
+
+/// Writes the fixed 312-byte `usbip_usb_device` struct (path+busid through
+/// `bNumInterfaces`), with no per-interface data. This is the entire body
+/// of an `op_rep_import` reply, and the part of `op_rep_devlist` shared
+/// with it.
+fn write_usbip_usb_device(stream: &mut impl Write, busid: &str, device: &DeviceInfo) -> Result<()> {
+    write_fixed_str(stream, &format!("/sys/devices/{}", busid), 256)?;
+    write_fixed_str(stream, busid, 32)?;
+    stream.write_all(&1u32.to_be_bytes())?; // busnum
+    stream.write_all(&1u32.to_be_bytes())?; // devnum
+    stream.write_all(&2u32.to_be_bytes())?; // speed: USB_SPEED_HIGH
+
+    let vendor_id = u16::from_str_radix(&device.vendor_id, 16).unwrap_or(0);
+    let product_id = u16::from_str_radix(&device.product_id, 16).unwrap_or(0);
+    stream.write_all(&vendor_id.to_be_bytes())?;
+    stream.write_all(&product_id.to_be_bytes())?;
+    stream.write_all(&0u16.to_be_bytes())?; // bcdDevice
+
+    let config = device.configurations.first();
+    stream.write_all(&[0])?; // bDeviceClass
+    stream.write_all(&[0])?; // bDeviceSubClass
+    stream.write_all(&[0])?; // bDeviceProtocol
+    stream.write_all(&[config.map(|c| c.number).unwrap_or(0)])?;
+    stream.write_all(&[device.num_configurations])?;
+    stream.write_all(&[config.map(|c| c.num_interfaces).unwrap_or(0)])?;
+
+    Ok(())
+}
+
+/// `op_rep_devlist` additionally appends a `usbip_usb_interface` entry per
+/// interface right after the `usbip_usb_device` struct; `op_rep_import`
+/// does not, so this helper is only used for the devlist reply.
+fn write_exported_device(stream: &mut impl Write, busid: &str, device: &DeviceInfo) -> Result<()> {
+    write_usbip_usb_device(stream, busid, device)?;
+
+    if let Some(config) = device.configurations.first() {
+        for interface in &config.interfaces {
+            stream.write_all(&[0x03])?; // bInterfaceClass: HID
+            stream.write_all(&[interface.subclass_code])?;
+            stream.write_all(&[0])?; // bInterfaceProtocol
+            stream.write_all(&[0])?; // padding
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_devlist(stream: &mut TcpStream, busid: &str, device: &DeviceInfo) -> Result<()> {
+    write_op_common(stream, OP_REP_DEVLIST, 0)?;
+    stream.write_all(&1u32.to_be_bytes())?; // ndevs
+    write_exported_device(stream, busid, device)
+}
+
+fn handle_import(
+    stream: &mut TcpStream,
+    requested_busid: &str,
+    busid: &str,
+    device: &DeviceInfo,
+) -> Result<bool> {
+    if requested_busid != busid {
+        write_op_common(stream, OP_REP_IMPORT, 1)?;
+        return Ok(false);
+    }
+
+    write_op_common(stream, OP_REP_IMPORT, 0)?;
+    write_usbip_usb_device(stream, busid, device)?;
+    Ok(true)
+}
+
+/// Writes a `USBIP_RET_SUBMIT` reply: the 20-byte base header, the 20-byte
+/// `ret_submit` fields, the 8-byte unused `setup`, then `response` itself —
+/// 48 bytes of header regardless of `response`'s length.
+fn write_ret_submit(
+    stream: &mut impl Write,
+    seqnum: u32,
+    devid: u32,
+    direction: u32,
+    ep: u32,
+    response: &[u8],
+) -> Result<()> {
+    stream.write_all(&USBIP_RET_SUBMIT.to_be_bytes())?;
+    stream.write_all(&seqnum.to_be_bytes())?;
+    stream.write_all(&devid.to_be_bytes())?;
+    stream.write_all(&direction.to_be_bytes())?;
+    stream.write_all(&ep.to_be_bytes())?;
+    stream.write_all(&0u32.to_be_bytes())?; // status
+    stream.write_all(&(response.len() as u32).to_be_bytes())?; // actual_length
+    stream.write_all(&0u32.to_be_bytes())?; // start_frame
+    stream.write_all(&0u32.to_be_bytes())?; // number_of_packets
+    stream.write_all(&0u32.to_be_bytes())?; // error_count
+    stream.write_all(&[0u8; 8])?; // setup (unused in RET_SUBMIT)
+    stream.write_all(response)?;
+    Ok(())
+}
+
+/// Writes a `USBIP_RET_UNLINK` reply. `usbip_header` is a fixed 48 bytes
+/// regardless of command: a 20-byte base plus a 28-byte command-specific
+/// union. `ret_unlink` only uses the first 4 bytes of that union (status),
+/// but the full 28 bytes still have to be written to keep the stream in
+/// sync with clients that always read 48-byte headers.
+fn write_ret_unlink(
+    stream: &mut impl Write,
+    seqnum: u32,
+    devid: u32,
+    direction: u32,
+    ep: u32,
+) -> Result<()> {
+    stream.write_all(&USBIP_RET_UNLINK.to_be_bytes())?;
+    stream.write_all(&seqnum.to_be_bytes())?;
+    stream.write_all(&devid.to_be_bytes())?;
+    stream.write_all(&direction.to_be_bytes())?;
+    stream.write_all(&ep.to_be_bytes())?;
+    stream.write_all(&0i32.to_be_bytes())?; // status
+    stream.write_all(&[0u8; 24])?; // padding
+    Ok(())
+}
+
+/// Serves USBIP_CMD_SUBMIT/USBIP_CMD_UNLINK requests for one attached
+/// client, driving `handler` for every URB until the connection closes.
+fn serve_attached_client(
+    stream: &mut TcpStream,
+    handler: &mut dyn UsbInterfaceHandler,
+) -> Result<()> {
+    loop {
+        let header = match read_exact_buf(stream, 48) {
+            Ok(header) => header,
+            Err(_) => return Ok(()),
+        };
+
+        let command = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let seqnum = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let devid = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        let direction = u32::from_be_bytes(header[12..16].try_into().unwrap());
+        let ep = u32::from_be_bytes(header[16..20].try_into().unwrap());
+
+        match command {
+            USBIP_CMD_SUBMIT => {
+                let transfer_buffer_length =
+                    u32::from_be_bytes(header[24..28].try_into().unwrap());
+                let setup = &header[40..48];
+                let setup_packet = if setup.iter().any(|b| *b != 0) {
+                    Some(setup.try_into().unwrap())
+                } else {
+                    None
+                };
+
+                let out_data = if direction == 0 {
+                    read_exact_buf(stream, transfer_buffer_length as usize)?
+                } else {
+                    Vec::new()
+                };
+
+                let interface = (devid & 0xFF) as u8;
+                let endpoint = if direction == 1 { ep as u8 | 0x80 } else { ep as u8 };
+                let response = handler.handle_urb(interface, endpoint, setup_packet, &out_data);
+                write_ret_submit(stream, seqnum, devid, direction, ep, &response)?;
+            }
+            USBIP_CMD_UNLINK => {
+                write_ret_unlink(stream, seqnum, devid, direction, ep)?;
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+fn handle_client(mut stream: TcpStream, busid: &str, device: &DeviceInfo) -> Result<()> {
+    loop {
+        let op_header = match read_exact_buf(&mut stream, 8) {
+            Ok(header) => header,
+            Err(_) => return Ok(()),
+        };
+        let code = u16::from_be_bytes(op_header[2..4].try_into().unwrap());
+
+        match code {
+            OP_REQ_DEVLIST => {
+                handle_devlist(&mut stream, busid, device)?;
+            }
+            OP_REQ_IMPORT => {
+                let busid_bytes = read_exact_buf(&mut stream, 32)?;
+                let requested_busid = String::from_utf8_lossy(&busid_bytes)
+                    .trim_end_matches('\0')
+                    .to_string();
+
+                if handle_import(&mut stream, &requested_busid, busid, device)? {
+                    let keymaps_json = std::fs::read_to_string("config.json").unwrap_or_default();
+                    let keymaps: Vec<Keymap> = serde_json::from_str(&keymaps_json).unwrap_or_default();
+                    let mut handler = KeymapReplayHandler::new(device.clone(), keymaps);
+                    return serve_attached_client(&mut stream, &mut handler);
+                }
+            }
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Runs a USB/IP server on `addr` (e.g. `"0.0.0.0:3240"`), exporting
+/// `device` under `busid` (e.g. `"1-1"`) until the process is killed.
+pub fn serve(addr: &str, busid: &str, device: DeviceInfo) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("[usbip] exporting {} as busid {} on {}", device.get_id(), busid, addr);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        println!("[usbip] client connected: {:?}", stream.peer_addr());
+        if let Err(e) = handle_client(stream, busid, &device) {
+            println!("[usbip] client error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClassCode, ConfigDescriptor, Interfaces};
+
+    fn sample_endpoint() -> Endpoint {
+        Endpoint {
+            max_packet_size: 8,
+            endpoint_number: 1,
+            interval: 10,
+            transfer_type: TransferType::Interrupt,
+            sync_type: SyncType::NoSynchronization,
+            address: 0x81,
+            direction: libusb::Direction::In,
+            usage_type: UsageType::Data,
+        }
+    }
+
+    fn sample_config() -> ConfigDescriptor {
+        ConfigDescriptor {
+            number: 1,
+            self_powered: false,
+            remote_wakeup: false,
+            max_power: 100,
+            num_interfaces: 1,
+            interfaces: vec![Interfaces {
+                number: 0,
+                interface_number: 0,
+                num_endpoints: 1,
+                class_code: ClassCode::HumanInterfaceDevice,
+                subclass_code: 1,
+                description_string_index: None,
+                endpoints: vec![sample_endpoint()],
+            }],
+        }
+    }
+
+    fn sample_device() -> DeviceInfo {
+        DeviceInfo {
+            vendor_id: "1234".to_string(),
+            product_id: "abcd".to_string(),
+            class_code: ClassCode::HumanInterfaceDevice,
+            subclass_code: 0,
+            protocol_code: 0,
+            max_packet_size: 64,
+            num_configurations: 1,
+            product_string_index: None,
+            manufacturer_string_index: None,
+            serial_number_string_index: None,
+            product: None,
+            manufacturer: None,
+            serial_number: None,
+            configurations: vec![sample_config()],
+        }
+    }
+
+    #[test]
+    fn ret_unlink_reply_is_48_bytes() {
+        let mut buf = Vec::new();
+        write_ret_unlink(&mut buf, 1, 2, 1, 0).unwrap();
+        assert_eq!(buf.len(), 48);
+    }
+
+    #[test]
+    fn ret_submit_reply_is_48_bytes_plus_response() {
+        let mut buf = Vec::new();
+        write_ret_submit(&mut buf, 1, 2, 1, 0, &[0xAA; 4]).unwrap();
+        assert_eq!(buf.len(), 48 + 4);
+    }
+
+    #[test]
+    fn config_descriptor_includes_interface_and_endpoint_sub_descriptors() {
+        let handler = KeymapReplayHandler::new(sample_device(), Vec::new());
+        let config = sample_config();
+        let bytes = handler.config_descriptor_bytes(&config);
+
+        // 9-byte config header + 9-byte interface + 7-byte endpoint.
+        assert_eq!(bytes.len(), 9 + 9 + 7);
+
+        let total_length = u16::from_le_bytes([bytes[2], bytes[3]]);
+        assert_eq!(total_length as usize, bytes.len());
+
+        let interface = &bytes[9..18];
+        assert_eq!(interface[1], 0x04); // bDescriptorType: INTERFACE
+        assert_eq!(interface[5], 0x03); // bInterfaceClass: HID
+
+        let endpoint = &bytes[18..25];
+        assert_eq!(endpoint[1], 0x05); // bDescriptorType: ENDPOINT
+        assert_eq!(endpoint[2], 0x81); // bEndpointAddress
+    }
+
+    #[test]
+    fn endpoint_attributes_packs_transfer_sync_and_usage_bits() {
+        let endpoint = sample_endpoint();
+        assert_eq!(endpoint_attributes(&endpoint), 0b0000_0011); // Interrupt, NoSync, Data
+    }
+
+    #[test]
+    fn usbip_usb_device_struct_is_312_bytes() {
+        let mut buf = Vec::new();
+        write_usbip_usb_device(&mut buf, "1-1", &sample_device()).unwrap();
+        assert_eq!(buf.len(), 312);
+    }
+}